@@ -0,0 +1,76 @@
+use rusqlite::{Connection, Result, Transaction};
+
+/// A single schema change, applied exactly once and in order.
+type Migration = fn(&Transaction) -> Result<()>;
+
+const MIGRATIONS: &[Migration] = &[
+    create_account_table,
+    split_balance_into_free_and_reserved,
+    create_transactions_table,
+];
+
+fn create_account_table(tx: &Transaction) -> Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS account(
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            account_number TEXT,
+            pin TEXT DEFAULT '000000',
+            balance INTEGER DEFAULT 0
+        )",
+        (),
+    )?;
+    Ok(())
+}
+
+/// Splits the single `balance` column into `free` (spendable) and
+/// `reserved` (held, e.g. for pending transfers), mirroring the Substrate
+/// balances pallet.
+fn split_balance_into_free_and_reserved(tx: &Transaction) -> Result<()> {
+    tx.execute("ALTER TABLE account RENAME COLUMN balance TO free", ())?;
+    tx.execute(
+        "ALTER TABLE account ADD COLUMN reserved INTEGER NOT NULL DEFAULT 0",
+        (),
+    )?;
+    Ok(())
+}
+
+/// Adds the append-only `transactions` ledger that every operation
+/// changing `free` (`deposit`, `withdraw`, `transfer`, `reserve`,
+/// `unreserve`, `repatriate_reserved`) records a row to, alongside the
+/// account mutation itself.
+fn create_transactions_table(tx: &Transaction) -> Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS transactions(
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            ts INTEGER NOT NULL,
+            kind TEXT NOT NULL,
+            origin_account TEXT,
+            target_account TEXT,
+            amount INTEGER NOT NULL,
+            resulting_balance INTEGER NOT NULL
+        )",
+        (),
+    )?;
+    Ok(())
+}
+
+/// Brings `db` up to the latest schema, recording progress in
+/// `PRAGMA user_version` so each migration runs at most once. Safe to call
+/// on every open: a fully up-to-date database is a no-op.
+pub fn run(db: &mut Connection) -> Result<()> {
+    let current_version: u32 = db.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    let current_version = current_version as usize;
+
+    if current_version >= MIGRATIONS.len() {
+        return Ok(());
+    }
+
+    let tx = db.transaction()?;
+    for migration in &MIGRATIONS[current_version..] {
+        migration(&tx)?;
+    }
+    tx.pragma_update(None, "user_version", MIGRATIONS.len() as u32)?;
+    tx.commit()?;
+
+    Ok(())
+}